@@ -38,6 +38,7 @@ use pbr::ProgressBar;
 use std::cmp::{max, min};
 use std::fs::File;
 use std::io::{Read, Write};
+use std::process::Command;
 use std::str::FromStr;
 
 mod binary;
@@ -47,7 +48,7 @@ pub use binary::errors::*;
 pub use binary::errors::ErrorKind::*;
 
 // subparse
-use subparse::{SubtitleEntry, SubtitleFormat, get_subtitle_format_err, parse_bytes};
+use subparse::{SubtitleEntry, SubtitleFile, SubtitleFormat, get_subtitle_format_err, parse_bytes};
 use subparse::timetypes::*;
 
 #[derive(Default)]
@@ -67,7 +68,19 @@ impl ProgressHandler for ProgressInfo {
     }
 }
 
+/// Path value which, instead of naming a real file, means "use stdin"/"use stdout" depending on context.
+const STREAM_PATH: &str = "-";
+
 fn read_file_to_bytes(path: &str) -> Result<Vec<u8>> {
+    if path == STREAM_PATH {
+        let mut v = Vec::new();
+        std::io::stdin()
+            .read_to_end(&mut v)
+            .map_err(|e| Error::from(Io(e)))
+            .chain_err(|| FileOperation(path.to_string()))?;
+        return Ok(v);
+    }
+
     let mut file = File::open(path).map_err(|e| Error::from(Io(e))).chain_err(
         || {
             FileOperation(path.to_string())
@@ -81,6 +94,13 @@ fn read_file_to_bytes(path: &str) -> Result<Vec<u8>> {
 }
 
 fn write_data_to_file(path: &str, d: Vec<u8>) -> Result<()> {
+    if path == STREAM_PATH {
+        return std::io::stdout()
+            .write_all(&d)
+            .map_err(|e| Error::from(Io(e)))
+            .chain_err(|| FileOperation(path.to_string()));
+    }
+
     let mut file = File::create(path)
         .map_err(|e| Error::from(Io(e)))
         .chain_err(|| FileOperation(path.to_string()))?;
@@ -174,19 +194,353 @@ fn get_truncated_deltas(timespans: &[TimeSpan], deltas: Vec<TimeDelta>) -> Vec<T
           .collect()
 }
 
+/// Sample rate (in Hz) used internally for voice-activity detection. Speech detection doesn't need
+/// more than this, and decoding to a lower rate keeps the ffmpeg step fast.
+const VAD_SAMPLE_RATE_HZ: u32 = 8000;
+
+/// Length of a single analysis frame for voice-activity detection.
+const VAD_FRAME_MS: i64 = 20;
+
+/// Voiced spans shorter than this are assumed to be noise spikes and dropped.
+const VAD_MIN_SPAN_MS: i64 = 150;
+
+/// Gaps between voiced spans shorter than this are bridged into a single span.
+const VAD_MAX_GAP_MS: i64 = 300;
+
+/// Number of trailing frames the noise floor is estimated from.
+const VAD_NOISE_WINDOW_FRAMES: usize = 100;
+
+/// A frame counts as voiced when its energy exceeds the noise floor by this factor.
+const VAD_VOICED_FACTOR: f64 = 3.0;
+
+/// Returns whether `path` looks like a media file (video/audio) rather than a subtitle file, based
+/// on its extension.
+fn is_media_file_path(path: &str) -> bool {
+    let lower = path.to_lowercase();
+    [".mkv", ".mp4", ".webm", ".avi", ".mov", ".wav", ".flac", ".m4a", ".mp3", ".ogg"]
+        .iter()
+        .any(|ext| lower.ends_with(ext))
+}
+
+/// Shells out to `ffmpeg` to decode `path` to mono 16-bit PCM at `sample_rate` Hz and returns the
+/// raw samples.
+fn decode_to_mono_pcm(path: &str, sample_rate: u32) -> Result<Vec<i16>> {
+    let output = Command::new("ffmpeg")
+        .args(
+            &[
+                "-v",
+                "error",
+                "-i",
+                path,
+                "-f",
+                "s16le",
+                "-ac",
+                "1",
+                "-ar",
+                &sample_rate.to_string(),
+                "-",
+            ],
+        )
+        .output()
+        .map_err(|e| Error::from(Io(e)))
+        .chain_err(|| FileOperation(path.to_string()))?;
+
+    if !output.status.success() {
+        bail!(
+            "ffmpeg failed to decode '{}' (exit status {:?}): {}",
+            path,
+            output.status.code(),
+            String::from_utf8_lossy(&output.stderr)
+        );
+    }
+
+    Ok(
+        output.stdout
+              .chunks(2)
+              .map(|c| i16::from_le_bytes([c[0], c.get(1).cloned().unwrap_or(0)]))
+              .collect(),
+    )
+}
+
+/// Converts a range of consecutive VAD frames into a `TimeSpan`.
+fn frames_to_timespan(start_frame: usize, end_frame: usize, frame_len: usize, sample_rate: u32) -> TimeSpan {
+    let ms_per_frame = (frame_len as i64 * 1000) / sample_rate as i64;
+    TimeSpan::new(
+        TimePoint::from_msecs(start_frame as i64 * ms_per_frame),
+        TimePoint::from_msecs(end_frame as i64 * ms_per_frame),
+    )
+}
+
+/// Runs simple energy-based voice-activity detection over `samples` (mono PCM at `sample_rate` Hz)
+/// and returns the detected speech spans.
+///
+/// Per-frame short-term energy is compared against a running noise floor (the 10th-percentile
+/// energy over a sliding window of recent frames); a frame is "voiced" when its energy exceeds the
+/// floor by `VAD_VOICED_FACTOR`. Consecutive voiced frames are merged into spans, short gaps
+/// between spans are bridged, and spans that are still too short to be real speech are dropped.
+fn detect_voice_activity_timespans(samples: &[i16], sample_rate: u32) -> Vec<TimeSpan> {
+    let frame_len = (sample_rate as i64 * VAD_FRAME_MS / 1000) as usize;
+    if frame_len == 0 || samples.is_empty() {
+        return Vec::new();
+    }
+
+    let energies: Vec<f64> = samples
+        .chunks(frame_len)
+        .map(|frame| frame.iter().map(|&s| f64::from(s) * f64::from(s)).sum())
+        .collect();
+
+    let mut voiced = vec![false; energies.len()];
+    let mut window: Vec<f64> = Vec::with_capacity(VAD_NOISE_WINDOW_FRAMES);
+    for (i, &energy) in energies.iter().enumerate() {
+        window.push(energy);
+        if window.len() > VAD_NOISE_WINDOW_FRAMES {
+            window.remove(0);
+        }
+
+        let mut sorted_window = window.clone();
+        sorted_window.sort_by(|a, b| a.partial_cmp(b).unwrap());
+        let noise_floor = sorted_window[sorted_window.len() / 10].max(1.0);
+
+        voiced[i] = energy > VAD_VOICED_FACTOR * noise_floor;
+    }
+
+    let mut spans: Vec<TimeSpan> = Vec::new();
+    let mut span_start: Option<usize> = None;
+    for (i, &is_voiced) in voiced.iter().enumerate() {
+        match (is_voiced, span_start) {
+            (true, None) => span_start = Some(i),
+            (false, Some(start)) => {
+                spans.push(frames_to_timespan(start, i, frame_len, sample_rate));
+                span_start = None;
+            }
+            _ => {}
+        }
+    }
+    if let Some(start) = span_start {
+        spans.push(frames_to_timespan(start, voiced.len(), frame_len, sample_rate));
+    }
+
+    let max_gap = TimeDelta::from_msecs(VAD_MAX_GAP_MS);
+    let mut bridged: Vec<TimeSpan> = Vec::new();
+    for span in spans {
+        let mut merged = false;
+        if let Some(last) = bridged.last_mut() {
+            if span.start - last.end < max_gap {
+                *last = TimeSpan::new(last.start, span.end);
+                merged = true;
+            }
+        }
+        if !merged {
+            bridged.push(span);
+        }
+    }
+
+    let min_span = TimeDelta::from_msecs(VAD_MIN_SPAN_MS);
+    bridged.into_iter().filter(|span| span.end - span.start >= min_span).collect()
+}
+
+/// Set when one of the file arguments is a stream (`-`), so that diagnostics are written to
+/// stderr instead of stdout, keeping stdout clean for piped subtitle data.
+static DIAGNOSTICS_TO_STDERR: std::sync::atomic::AtomicBool = std::sync::atomic::AtomicBool::new(false);
+
 /// Prints warning.
 fn pwarning<'a, T: Into<std::borrow::Cow<'a, str>>>(s: T) {
-    println!("WW: {}", s.into());
+    if DIAGNOSTICS_TO_STDERR.load(std::sync::atomic::Ordering::Relaxed) {
+        eprintln!("WW: {}", s.into());
+    } else {
+        println!("WW: {}", s.into());
+    }
 }
 
 /// Prints info.
 fn pinfo<'a, T: Into<std::borrow::Cow<'a, str>>>(s: T) {
-    println!("II: {}", s.into());
+    if DIAGNOSTICS_TO_STDERR.load(std::sync::atomic::Ordering::Relaxed) {
+        eprintln!("II: {}", s.into());
+    } else {
+        println!("II: {}", s.into());
+    }
 }
 
 /// Prints error.
 fn perror<'a, T: Into<std::borrow::Cow<'a, str>>>(s: T) {
-    println!("EE: {}", s.into());
+    if DIAGNOSTICS_TO_STDERR.load(std::sync::atomic::Ordering::Relaxed) {
+        eprintln!("EE: {}", s.into());
+    } else {
+        println!("EE: {}", s.into());
+    }
+}
+
+/// Prints a debug/blank diagnostic line, respecting `DIAGNOSTICS_TO_STDERR` like `pinfo`/`pwarning`/`perror`.
+fn pdiag<'a, T: Into<std::borrow::Cow<'a, str>>>(s: T) {
+    if DIAGNOSTICS_TO_STDERR.load(std::sync::atomic::Ordering::Relaxed) {
+        eprintln!("{}", s.into());
+    } else {
+        println!("{}", s.into());
+    }
+}
+
+/// Maps a `--format-*` option value to the `SubtitleFormat` it names.
+fn parse_subtitle_format_name(name: &str) -> Result<SubtitleFormat> {
+    match name {
+        "srt" => Ok(SubtitleFormat::SubRip),
+        "ass" | "ssa" => Ok(SubtitleFormat::SubStationAlpha),
+        "idx" => Ok(SubtitleFormat::VobSubIdx),
+        "sub" => Ok(SubtitleFormat::MicroDVD),
+        _ => bail!("unknown subtitle format '{}' (expected one of: srt, ass, ssa, idx, sub)", name),
+    }
+}
+
+/// Determines the `SubtitleFormat` of `path`, preferring an explicit `--format-*` override over
+/// sniffing the extension/content. Streams (`-`) have no extension to sniff, so an override is
+/// required for them.
+fn resolve_subtitle_format(path: &str, data: &[u8], format_override: Option<&str>) -> Result<SubtitleFormat> {
+    if let Some(name) = format_override {
+        return parse_subtitle_format_name(name);
+    }
+    if path == STREAM_PATH {
+        bail!(
+            "reading/writing '-' (a stream) requires an explicit --format-ref/--format-inc/--format-out option, since the format can't be sniffed from a file extension"
+        );
+    }
+    get_subtitle_format_err(path, data).chain_err(|| ErrorKind::FileOperation(path.to_string()))
+}
+
+/// Builds a fresh subtitle file of `target_format` out of `entries`, carrying over the plain
+/// dialogue text. Used when the incorrect and output formats differ, since this program otherwise
+/// only ever rewrites a file's own format in-place.
+fn convert_entries_to_format(target_format: SubtitleFormat, entries: &[SubtitleEntry], fps: f64) -> Result<SubtitleFile> {
+    SubtitleFile::new(target_format, entries, fps).chain_err(|| {
+        ErrorKind::FileOperation(format!("<conversion to {:?}>", target_format))
+    })
+}
+
+/// Parses one `HH:MM:SS,mmm` (or `.`-separated) SubRip timestamp.
+fn parse_srt_timestamp(s: &str) -> Option<TimePoint> {
+    let normalized = s.trim().replace('.', ",");
+    let comma_idx = normalized.find(',')?;
+    let (hms, ms_str) = (&normalized[..comma_idx], &normalized[comma_idx + 1..]);
+    let ms: i64 = ms_str.trim().parse().ok()?;
+
+    let hms_parts: Vec<&str> = hms.split(':').collect();
+    let (h, m, s): (i64, i64, i64) = match hms_parts.as_slice() {
+        [h, m, s] => (h.parse().ok()?, m.parse().ok()?, s.parse().ok()?),
+        _ => return None,
+    };
+
+    Some(TimePoint::from_msecs((h * 3600 + m * 60 + s) * 1000 + ms))
+}
+
+/// Parses the `HH:MM:SS,mmm --> HH:MM:SS,mmm` timing line of a single SubRip block.
+fn parse_srt_block_timespan(block: &str) -> Option<TimeSpan> {
+    for line in block.lines() {
+        if let Some(arrow_idx) = line.find("-->") {
+            let start = parse_srt_timestamp(&line[..arrow_idx])?;
+            let end = parse_srt_timestamp(&line[arrow_idx + 3..])?;
+            return Some(TimeSpan::new(start, end));
+        }
+    }
+    None
+}
+
+/// Re-scans `data` block by block (blocks are separated by a blank line, as in the SubRip format)
+/// and keeps whichever blocks have a parseable timing line, discarding only the damaged ones. This
+/// is the per-entry recovery `--skip-errors` relies on when subparse's own parser gives up. Only
+/// SubRip has a text structure we can safely resync on this way; for every other format a parse
+/// failure still means the whole file is unrecoverable.
+fn lenient_parse_entries(format: SubtitleFormat, data: &[u8], encoding: encoding::EncodingRef, path: &str) -> (Vec<SubtitleEntry>, usize, usize) {
+    if format != SubtitleFormat::SubRip {
+        pwarning(format!(
+            "'{}' could not be parsed, and --skip-errors has no block-level recovery for this format (only SubRip/.srt is supported); all of its entries are discarded",
+            path
+        ));
+        return (Vec::new(), 0, 0);
+    }
+
+    let text = match encoding::Encoding::decode(encoding, data, encoding::DecoderTrap::Replace) {
+        Ok(text) => text,
+        Err(e) => {
+            pwarning(format!("'{}' could not even be decoded as text ({}); all of its entries are discarded", path, e));
+            return (Vec::new(), 0, 0);
+        }
+    };
+
+    let mut entries = Vec::new();
+    let mut dropped = 0;
+    let mut total = 0;
+    let mut line_number = 1;
+
+    for block in text.replace("\r\n", "\n").split("\n\n") {
+        let block_line_count = block.lines().count() + 1;
+        if block.trim().is_empty() {
+            line_number += block_line_count;
+            continue;
+        }
+
+        total += 1;
+        match parse_srt_block_timespan(block) {
+            Some(timespan) => {
+                // the dialogue text is whatever follows the timing line in the block
+                let text: Vec<&str> = block.lines().skip_while(|l| !l.contains("-->")).skip(1).collect();
+                entries.push(SubtitleEntry {
+                    timespan: timespan,
+                    line: Some(text.join("\n")),
+                });
+            }
+            None => {
+                dropped += 1;
+                pwarning(format!("skipping damaged subtitle block near line {} of '{}' (no parseable timing line)", line_number, path));
+            }
+        }
+
+        line_number += block_line_count;
+    }
+
+    (entries, dropped, total)
+}
+
+/// Parses and extracts the subtitle entries of `path`, tolerating damage when `skip_errors` is
+/// set: a failure at either `parse_bytes` or `get_subtitle_entries` falls back to
+/// `lenient_parse_entries`, which keeps whatever blocks parse and reports how many were dropped.
+/// Returns the parsed `SubtitleFile` when subparse's own parser succeeded (needed to rewrite a
+/// file in its own format later on), the recovered entries, and whether anything was dropped.
+fn load_subtitle_entries_resilient(
+    format: SubtitleFormat,
+    data: &[u8],
+    encoding: encoding::EncodingRef,
+    fps: f64,
+    path: &str,
+    skip_errors: bool,
+) -> Result<(Option<SubtitleFile>, Vec<SubtitleEntry>, bool)> {
+    let parsed_file = match parse_bytes(format, data, encoding, fps) {
+        Ok(file) => Some(file),
+        Err(e) => {
+            if !skip_errors {
+                return Err(e).chain_err(|| FileOperation(path.to_string()));
+            }
+            pwarning(format!("'{}' failed to parse as {:?} ({}); attempting block-level recovery", path, format, e));
+            None
+        }
+    };
+
+    if let Some(file) = parsed_file {
+        match file.get_subtitle_entries() {
+            Ok(entries) => return Ok((Some(file), entries, false)),
+            Err(e) => {
+                if !skip_errors {
+                    return Err(e).chain_err(|| FileOperation(path.to_string()));
+                }
+                pwarning(format!("'{}' parsed but its entries failed validation ({}); attempting block-level recovery", path, e));
+            }
+        }
+    }
+
+    let (entries, dropped, total) = lenient_parse_entries(format, data, encoding, path);
+    if total > 0 {
+        pinfo(format!("recovered {} of {} subtitle blocks in '{}' ({} discarded)", total - dropped, total, path, dropped));
+    } else if entries.is_empty() {
+        pwarning(format!("'{}' could not be recovered at all; all entries were discarded", path));
+    }
+    Ok((None, entries, dropped > 0 || entries.is_empty()))
 }
 
 /// Does reading, parsing and nice error handling for a f64 clap parameter.
@@ -205,19 +559,120 @@ fn unpack_clap_number_i64(matches: &clap::ArgMatches, parameter_name: &'static s
     })
 }
 
-fn run() -> Result<()> {
+/// Reads an optional non-negative integer clap parameter, used for `--from-index`/`--to-index`.
+fn parse_optional_index_arg(matches: &clap::ArgMatches, parameter_name: &'static str) -> Result<Option<usize>> {
+    if !matches.is_present(parameter_name) {
+        return Ok(None);
+    }
+    let value: i64 = unpack_clap_number_i64(matches, parameter_name)?;
+    if value < 0 {
+        return Err(Error::from(ExpectedPositiveNumber(value))).chain_err(|| Error::from(InvalidArgument(parameter_name)));
+    }
+    Ok(Some(value as usize))
+}
+
+/// Parses a time value for `--shift`/`--rescale`: plain seconds ("12.5"), "M:S" or "H:M:S", with
+/// '.' or ',' as the decimal separator and an optional leading sign.
+fn parse_time_str(s: &str) -> Result<TimeDelta> {
+    let normalized = s.replace(',', ".");
+    let (sign, unsigned): (f64, &str) = if let Some(stripped) = normalized.strip_prefix('-') {
+        (-1.0, stripped)
+    } else {
+        (1.0, normalized.as_str())
+    };
+
+    let parts: Vec<&str> = unsigned.split(':').collect();
+    let parse_part = |part: &str| -> Result<f64> {
+        FromStr::from_str(part).chain_err(|| ArgumentParseError("time", s.to_string()))
+    };
+
+    let seconds = match parts.as_slice() {
+        [secs] => parse_part(secs)?,
+        [mins, secs] => parse_part(mins)? * 60.0 + parse_part(secs)?,
+        [hours, mins, secs] => parse_part(hours)? * 3600.0 + parse_part(mins)? * 60.0 + parse_part(secs)?,
+        _ => bail!("invalid time value '{}' (expected seconds, 'M:S' or 'H:M:S')", s),
+    };
+
+    Ok(TimeDelta::from_msecs((sign * seconds * 1000.0).round() as i64))
+}
+
+/// Parses a `--from-time`/`--to-time` value (same syntax as `--shift`) as an absolute `TimePoint`.
+fn parse_time_point_str(s: &str) -> Result<TimePoint> {
+    Ok(TimePoint::from_msecs(parse_time_str(s)?.msecs()))
+}
+
+/// Parses a single `t=t'` anchor pair for `--rescale`, returning both times in milliseconds.
+fn parse_rescale_anchor(s: &str) -> Result<(f64, f64)> {
+    match s.find('=') {
+        Some(eq_idx) => {
+            let from = parse_time_str(&s[..eq_idx])?.msecs() as f64;
+            let to = parse_time_str(&s[eq_idx + 1..])?.msecs() as f64;
+            Ok((from, to))
+        }
+        None => bail!("invalid --rescale anchor '{}' (expected 't=t\\'')", s),
+    }
+}
+
+/// Parses a `--rescale` argument into the affine map `t' = a*t + b` (in milliseconds), either from
+/// an `<old_fps>:<new_fps>` frame-rate ratio or from two `<t1>=<t1'>,<t2>=<t2'>` anchor points.
+fn parse_rescale_arg(s: &str) -> Result<(f64, f64)> {
+    // anchor pairs always contain '=' (and the time syntax they embed may itself contain ':'), so
+    // check for that form first instead of dispatching on the first ':' found
+    if !s.contains('=') {
+        if let Some(colon_idx) = s.find(':') {
+            let old_fps: f64 = FromStr::from_str(&s[..colon_idx]).chain_err(|| ArgumentParseError("rescale", s.to_string()))?;
+            let new_fps: f64 = FromStr::from_str(&s[colon_idx + 1..]).chain_err(|| ArgumentParseError("rescale", s.to_string()))?;
+            if old_fps <= 0.0 || new_fps <= 0.0 {
+                bail!("--rescale frame rates must be positive (got '{}')", s);
+            }
+            return Ok((old_fps / new_fps, 0.0));
+        }
+    }
+
+    let anchors: Vec<&str> = s.split(',').collect();
+    match anchors.as_slice() {
+        [first, second] => {
+            let (t1, t1p) = parse_rescale_anchor(first)?;
+            let (t2, t2p) = parse_rescale_anchor(second)?;
+            if (t2 - t1).abs() < std::f64::EPSILON {
+                bail!("--rescale anchor points must have distinct times (got '{}')", s);
+            }
+            let a = (t2p - t1p) / (t2 - t1);
+            let b = t1p - a * t1;
+            Ok((a, b))
+        }
+        _ => bail!("invalid --rescale value '{}' (expected '<old_fps>:<new_fps>' or '<t1>=<t1\\'>,<t2>=<t2\\'>')", s),
+    }
+}
+
+fn run() -> Result<bool> {
     let matches = App::new(PKG_NAME.unwrap_or("unkown (not compiled with cargo)"))
         .version(PKG_VERSION.unwrap_or("unknown (not compiled with cargo)"))
         .about(PKG_DESCRIPTION.unwrap_or("unknown (not compiled with cargo)"))
         .arg(Arg::with_name("reference-sub-file")
-            .help("Path to the reference subtitle file")
-            .required(true))
+            .help("Path to the reference subtitle file (or '-' to read from stdin; requires --format-ref). Not needed together with --shift or --rescale.")
+            .required_unless_one(&["shift", "rescale"]))
         .arg(Arg::with_name("incorrect-sub-file")
-            .help("Path to the incorrect subtitle file")
+            .help("Path to the incorrect subtitle file (or '-' to read from stdin; requires --format-inc)")
             .required(true))
         .arg(Arg::with_name("output-file-path")
-            .help("Path to corrected subtitle file")
+            .help("Path to corrected subtitle file (or '-' to write to stdout; requires --format-out). May use a different subtitle format than the incorrect file; the file is converted, dropping any styling that the target format can't represent.")
             .required(true))
+        .arg(Arg::with_name("format-ref")
+            .long("format-ref")
+            .value_name("srt|ass|ssa|idx|sub")
+            .possible_values(&["srt", "ass", "ssa", "idx", "sub"])
+            .help("Overrides the reference subtitle format instead of sniffing it from the file extension/content. Mandatory when the reference path is '-'."))
+        .arg(Arg::with_name("format-inc")
+            .long("format-inc")
+            .value_name("srt|ass|ssa|idx|sub")
+            .possible_values(&["srt", "ass", "ssa", "idx", "sub"])
+            .help("Overrides the incorrect subtitle format instead of sniffing it from the file extension/content. Mandatory when the incorrect path is '-'."))
+        .arg(Arg::with_name("format-out")
+            .long("format-out")
+            .value_name("srt|ass|ssa|idx|sub")
+            .possible_values(&["srt", "ass", "ssa", "idx", "sub"])
+            .help("Overrides the output subtitle format instead of sniffing it from the file extension/content. Mandatory when the output path is '-'."))
         .arg(Arg::with_name("split-penalty")
             .short("p")
             .long("split-penalty")
@@ -252,12 +707,45 @@ fn run() -> Result<()> {
             .long("encoding-inc")
             .default_value("utf-8")
             .help("Charset encoding of the incorrect subtitle file."))
-        .after_help("This program works with .srt, .ass/.ssa, .idx and .sub files. The corrected file will have the same format as the incorrect file.")
+        .arg(Arg::with_name("skip-errors")
+            .long("skip-errors")
+            .help("Skips subtitle entries that fail to parse (e.g. bad timestamp lines, a truncated final entry) instead of aborting; a warning is printed per skipped entry. The exit code is 2 if any entries were dropped this way."))
+        .arg(Arg::with_name("shift")
+            .long("shift")
+            .value_name("TIME")
+            .conflicts_with("rescale")
+            .help("Shifts every subtitle in the incorrect file by a fixed signed offset instead of running the alignment algorithm; no reference file is needed. Accepts plain seconds ('12.5', '-3'), 'M:S' or 'H:M:S', with '.' or ',' as the decimal separator."))
+        .arg(Arg::with_name("rescale")
+            .long("rescale")
+            .value_name("old_fps:new_fps | t1=t1',t2=t2'")
+            .conflicts_with("shift")
+            .help("Applies a linear time rescale (t' = a*t + b) to the start time of every subtitle and moves each subtitle by the resulting offset, instead of running the alignment algorithm; subtitle durations are not rescaled. No reference file is needed. Accepts '<old_fps>:<new_fps>' for frame-rate conversion, or two anchor points '<t1>=<t1'>,<t2>=<t2'>' (same time syntax as --shift) to derive the affine map directly."))
+        .arg(Arg::with_name("from-time")
+            .long("from-time")
+            .value_name("TIME")
+            .conflicts_with_all(&["shift", "rescale"])
+            .help("Only realigns incorrect-file lines that end at or after this time; lines entirely before it keep their original timing. Same time syntax as --shift. Only applies to the reference-based alignment, not --shift/--rescale."))
+        .arg(Arg::with_name("to-time")
+            .long("to-time")
+            .value_name("TIME")
+            .conflicts_with_all(&["shift", "rescale"])
+            .help("Only realigns incorrect-file lines that start at or before this time; lines entirely after it keep their original timing. Same time syntax as --shift. Only applies to the reference-based alignment, not --shift/--rescale."))
+        .arg(Arg::with_name("from-index")
+            .long("from-index")
+            .value_name("integer")
+            .conflicts_with_all(&["shift", "rescale"])
+            .help("Only realigns incorrect-file lines from this 0-based index onwards; earlier lines keep their original timing. Only applies to the reference-based alignment, not --shift/--rescale."))
+        .arg(Arg::with_name("to-index")
+            .long("to-index")
+            .value_name("integer")
+            .conflicts_with_all(&["shift", "rescale"])
+            .help("Only realigns incorrect-file lines up to and including this 0-based index; later lines keep their original timing. Only applies to the reference-based alignment, not --shift/--rescale."))
+        .after_help("This program works with .srt, .ass/.ssa, .idx and .sub files. The corrected file defaults to the same format as the incorrect file, but can be converted to any of the supported formats by giving the output path a different extension (or via --format-out); converting drops any styling the target format can't represent. The reference file may also be a video/audio file (e.g. .mkv, .mp4, .wav) - speech is then detected with voice-activity detection and used as the reference timings; this requires `ffmpeg` to be installed and on PATH.")
         .get_matches();
 
     // 开始执行主逻辑
     let incorrect_file_path = matches.value_of("incorrect-sub-file").unwrap();
-    let reference_file_path = matches.value_of("reference-sub-file").unwrap();
+    let reference_file_path = matches.value_of("reference-sub-file");
     let output_file_path = matches.value_of("output-file-path").unwrap();
 
     let interval: i64 = unpack_clap_number_i64(&matches, "interval")?;
@@ -287,115 +775,190 @@ fn run() -> Result<()> {
             Error::from(UnknownEncoding(encoding_label_inc.to_string()))
         })?;
 
-    let reference_sub_data = read_file_to_bytes(reference_file_path)?;
+    let uses_stream = reference_file_path == Some(STREAM_PATH) || incorrect_file_path == STREAM_PATH ||
+        output_file_path == STREAM_PATH;
+    DIAGNOSTICS_TO_STDERR.store(uses_stream, std::sync::atomic::Ordering::Relaxed);
+
+    let format_ref_override = matches.value_of("format-ref");
+    let format_inc_override = matches.value_of("format-inc");
+    let format_out_override = matches.value_of("format-out");
+
+    let shift_arg = matches.value_of("shift");
+    let rescale_arg = matches.value_of("rescale");
+
+    let from_time = match matches.value_of("from-time") {
+        Some(s) => Some(parse_time_point_str(s)?),
+        None => None,
+    };
+    let to_time = match matches.value_of("to-time") {
+        Some(s) => Some(parse_time_point_str(s)?),
+        None => None,
+    };
+    let from_index = parse_optional_index_arg(&matches, "from-index")?;
+    let to_index = parse_optional_index_arg(&matches, "to-index")?;
+
+    let skip_errors = matches.is_present("skip-errors");
+    let mut any_entries_dropped = false;
+
     let incorrect_sub_data = read_file_to_bytes(incorrect_file_path)?;
 
-    let reference_file_format = get_subtitle_format_err(reference_file_path, &reference_sub_data)
-        .chain_err(|| ErrorKind::FileOperation(reference_file_path.to_string()))?;
-    let incorrect_file_format = get_subtitle_format_err(incorrect_file_path, &incorrect_sub_data)
-        .chain_err(|| ErrorKind::FileOperation(incorrect_file_path.to_string()))?;
-    let output_file_format = get_subtitle_format_err(output_file_path, &incorrect_sub_data)
-        .chain_err(|| ErrorKind::FileOperation(output_file_path.to_string()))?; // HACK: to hint the right output format, the input data is provided
-
-    // this program internally stores the files in a non-destructable way (so
-    // formatting is preserved) but has no abilty to convert between formats
-    if incorrect_file_format != output_file_format {
-        return Err(
-            DifferentOutputFormat(
-                incorrect_file_path.to_string(),
-                output_file_path.to_string(),
-            )
-            .into(),
-        );
-    }
+    let incorrect_file_format = resolve_subtitle_format(incorrect_file_path, &incorrect_sub_data, format_inc_override)?;
+    // HACK: the output file doesn't exist yet, so sniffing falls back to the incorrect file's data
+    // to hint the right format; resolve_subtitle_format still takes care of --format-out and of
+    // rejecting an unresolvable '-' output path
+    let output_file_format = resolve_subtitle_format(output_file_path, &incorrect_sub_data, format_out_override)?;
 
-    let timed_reference_file = parse_bytes(
-        reference_file_format,
-        &reference_sub_data,
-        encoding_ref,
-        sub_fps_inc,
-    )
-                               .chain_err(|| FileOperation(reference_file_path.to_string()))?;
-    let timed_incorrect_file = parse_bytes(
+    let (timed_incorrect_file, incorrect_entries, incorrect_entries_dropped) = load_subtitle_entries_resilient(
         incorrect_file_format,
         &incorrect_sub_data,
         encoding_inc,
         sub_fps_ref,
-    )
-                               .chain_err(|| FileOperation(incorrect_file_path.to_string()))?;
-
-    let timings_reference = corrected_timings(
-        timed_reference_file.get_subtitle_entries()?
-                            .into_iter()
-                            .map(|subentry| subentry.timespan)
-                            .collect(),
-    );
+        incorrect_file_path,
+        skip_errors,
+    )?;
+    any_entries_dropped |= incorrect_entries_dropped;
+    // kept alongside the timings so a cross-format conversion can still carry the dialogue text over
+    let incorrect_lines: Vec<Option<String>> = incorrect_entries.iter().map(|subentry| subentry.line.clone()).collect();
     let timings_incorrect = corrected_timings(
-        timed_incorrect_file.get_subtitle_entries()?
-                            .into_iter()
-                            .map(|subentry| subentry.timespan)
-                            .collect(),
+        incorrect_entries.into_iter()
+                         .map(|subentry| subentry.timespan)
+                         .collect(),
     );
 
-    let alg_reference_timespans = timings_to_alg_timespans(&timings_reference, interval);
-    let alg_incorrect_timespans = timings_to_alg_timespans(&timings_incorrect, interval);
+    let mut deltas: Vec<TimeDelta> = if let Some(shift_str) = shift_arg {
+        let shift = parse_time_str(shift_str)?;
+        vec![shift; timings_incorrect.len()]
+    } else if let Some(rescale_str) = rescale_arg {
+        let (a, b) = parse_rescale_arg(rescale_str)?;
+        timings_incorrect.iter()
+                         .map(|timespan| {
+            let new_start_ms = (a * (timespan.start.msecs() as f64) + b).round() as i64;
+            TimeDelta::from_msecs(new_start_ms - timespan.start.msecs())
+        })
+                         .collect()
+    } else {
+        let reference_file_path = reference_file_path.unwrap();
+        let reference_is_media = is_media_file_path(reference_file_path);
 
-    let alg_deltas = align(
-        alg_incorrect_timespans.clone(),
-        alg_reference_timespans,
-        split_penalty / 100.0,
-        Some(Box::new(ProgressInfo::default())),
-    );
-    let mut deltas = alg_deltas_to_timing_deltas(&alg_deltas, interval);
-    println!("alg_deltas {:?}", alg_deltas);
-    println!("deltas {:?}", deltas);
-
-
-    // list of original subtitles lines which have the same timings
-    // 将每句偏移合并
-    let shift_groups: Vec<(AlgTimeDelta, Vec<TimeSpan>)> = get_subtitle_delta_groups(
-        alg_deltas.iter()
-                  .cloned()
-                  .zip(timings_incorrect.iter().cloned())
-                  .collect(),
-    );
-    println!("shift_groups {:?}", shift_groups);
-
-    // shift_groups记录了平移的信息，以下代码做输出展示。
-    for (shift_group_delta, shift_group_lines) in shift_groups {
-        // computes the first and last timestamp for all lines with that delta
-        // -> that way we can provide the user with an information like
-        //     "100 subtitles with 10min length"
-        let min_max_opt = shift_group_lines.iter().fold(None, |last_opt, subline| {
-            let new_min = subline.start;
-            let new_max = subline.end;
-            if let Some((last_min, last_max)) = last_opt {
-                Some((min(last_min, new_min), max(last_max, new_max)))
-            } else {
-                Some((new_min, new_max))
-            }
-        });
+        let timings_reference = if reference_is_media {
+            pinfo(format!("detecting speech in reference media file '{}' (this may take a while)", reference_file_path));
+            let samples = decode_to_mono_pcm(reference_file_path, VAD_SAMPLE_RATE_HZ)?;
+            detect_voice_activity_timespans(&samples, VAD_SAMPLE_RATE_HZ)
+        } else {
+            let reference_sub_data = read_file_to_bytes(reference_file_path)?;
+            let reference_file_format = resolve_subtitle_format(reference_file_path, &reference_sub_data, format_ref_override)?;
+            let (_timed_reference_file, reference_entries, reference_entries_dropped) = load_subtitle_entries_resilient(
+                reference_file_format,
+                &reference_sub_data,
+                encoding_ref,
+                sub_fps_inc,
+                reference_file_path,
+                skip_errors,
+            )?;
+            any_entries_dropped |= reference_entries_dropped;
 
-        let (min, max) = match min_max_opt {
-            Some(v) => v,
-            None => unreachable!(),
+            corrected_timings(
+                reference_entries.into_iter()
+                                 .map(|subentry| subentry.timespan)
+                                 .collect(),
+            )
         };
 
-        pinfo(format!(
-            "shifted block of {} subtitles with length {} by {}",
-            shift_group_lines.len(),
-            max - min,
-            alg_delta_to_delta(shift_group_delta, interval)
-        ));
-    }
+        // partition the incorrect lines into the window that gets realigned and the lines outside
+        // of it, which are written through with a zero delta
+        let (in_window_indices, in_window_timespans): (Vec<usize>, Vec<TimeSpan>) = timings_incorrect
+            .iter()
+            .cloned()
+            .enumerate()
+            .filter(|&(index, timespan)| {
+                from_index.map_or(true, |from| index >= from) && to_index.map_or(true, |to| index <= to) &&
+                    from_time.map_or(true, |from| timespan.end >= from) &&
+                    to_time.map_or(true, |to| timespan.start <= to)
+            })
+            .unzip();
 
+        let alg_reference_timespans = timings_to_alg_timespans(&timings_reference, interval);
+        let alg_incorrect_timespans = timings_to_alg_timespans(&in_window_timespans, interval);
+
+        // the progress bar writes straight to stdout, which must stay clean when streaming
+        let progress_handler: Option<Box<ProgressHandler>> = if uses_stream {
+            None
+        } else {
+            Some(Box::new(ProgressInfo::default()))
+        };
+        let alg_deltas = align(
+            alg_incorrect_timespans.clone(),
+            alg_reference_timespans,
+            split_penalty / 100.0,
+            progress_handler,
+        );
+        let in_window_deltas = alg_deltas_to_timing_deltas(&alg_deltas, interval);
+        pdiag(format!("alg_deltas {:?}", alg_deltas));
+        pdiag(format!("deltas {:?}", in_window_deltas));
+
+        // merge the in-window deltas back into a full-length vector, filling excluded lines with a
+        // zero delta so they're written through unchanged
+        let mut deltas = vec![TimeDelta::from_msecs(0); timings_incorrect.len()];
+        for (&original_index, &delta) in in_window_indices.iter().zip(in_window_deltas.iter()) {
+            deltas[original_index] = delta;
+        }
+
+        if in_window_indices.len() < timings_incorrect.len() {
+            pinfo(format!(
+                "{} of {} subtitle lines are outside of the selected window and were left untouched",
+                timings_incorrect.len() - in_window_indices.len(),
+                timings_incorrect.len()
+            ));
+        }
+
+        // list of original subtitles lines which have the same timings
+        // 将每句偏移合并
+        let shift_groups: Vec<(AlgTimeDelta, Vec<TimeSpan>)> = get_subtitle_delta_groups(
+            alg_deltas.iter()
+                      .cloned()
+                      .zip(in_window_timespans.iter().cloned())
+                      .collect(),
+        );
+        pdiag(format!("shift_groups {:?}", shift_groups));
+
+        // shift_groups记录了平移的信息，以下代码做输出展示。
+        for (shift_group_delta, shift_group_lines) in shift_groups {
+            // computes the first and last timestamp for all lines with that delta
+            // -> that way we can provide the user with an information like
+            //     "100 subtitles with 10min length"
+            let min_max_opt = shift_group_lines.iter().fold(None, |last_opt, subline| {
+                let new_min = subline.start;
+                let new_max = subline.end;
+                if let Some((last_min, last_max)) = last_opt {
+                    Some((min(last_min, new_min), max(last_max, new_max)))
+                } else {
+                    Some((new_min, new_max))
+                }
+            });
+
+            let (min, max) = match min_max_opt {
+                Some(v) => v,
+                None => unreachable!(),
+            };
+
+            pinfo(format!(
+                "shifted block of {} subtitles with length {} by {}",
+                shift_group_lines.len(),
+                max - min,
+                alg_delta_to_delta(shift_group_delta, interval)
+            ));
+        }
+
+        if timings_reference.is_empty() {
+            pdiag("");
+            pwarning("reference file has no subtitle lines");
+        }
+
+        deltas
+    };
 
-    if timings_reference.is_empty() {
-        println!("");
-        pwarning("reference file has no subtitle lines");
-    }
     if timings_incorrect.is_empty() {
-        println!("");
+        pdiag("");
         pwarning("file with incorrect subtitles has no lines");
     }
 
@@ -404,7 +967,7 @@ fn run() -> Result<()> {
         (delta + timespan.start).is_negative()
     });
     if writing_negative_timespans {
-        println!("");
+        pdiag("");
         pwarning(
             "some subtitles now have negative timings, which can cause invalid subtitle files",
         );
@@ -420,32 +983,60 @@ fn run() -> Result<()> {
         }
     }
 
-    // .idx only has start timepoints (the subtitle is shown until the next subtitle starts) - so retiming with gaps might
-    // produce errors
-    if output_file_format == SubtitleFormat::VobSubIdx {
-        println!("");
+    // .idx and MicroDVD only store start timepoints/frame numbers (the subtitle is shown until the
+    // next one starts) - so retiming with gaps might produce errors
+    if output_file_format == SubtitleFormat::VobSubIdx || output_file_format == SubtitleFormat::MicroDVD {
+        pdiag("");
         pwarning(
-            "writing to an '.idx' file can lead to unexpected results due to restrictions of this format",
+            "writing to this output format can lead to unexpected results, since its timing model is lossy (start-only/frame-based)",
         );
     }
 
-    // incorrect file -> correct file
+    // incorrect file -> correct file; the dialogue text rides along so a cross-format conversion
+    // (which rebuilds the `SubtitleFile` from scratch) doesn't end up with blank lines
     let shifted_timespans: Vec<SubtitleEntry> = timings_incorrect.iter()
                                                                  .zip(deltas.iter())
-                                                                 .map(|(&timespan, &delta)| SubtitleEntry::from(timespan + delta))
+                                                                 .zip(incorrect_lines.iter())
+                                                                 .map(|((&timespan, &delta), line)| {
+        SubtitleEntry {
+            timespan: timespan + delta,
+            line: line.clone(),
+        }
+    })
                                                                  .collect();
 
-    // write corrected files
-    let mut correct_file = timed_incorrect_file.clone();
-    correct_file.update_subtitle_entries(&shifted_timespans)?;
+    // write corrected file, converting between formats if the incorrect and output files differ, or
+    // if --skip-errors had to fall back to block-level recovery and left us without a real parsed
+    // `SubtitleFile` template to update in place
+    let correct_file = match timed_incorrect_file {
+        Some(mut f) if output_file_format == incorrect_file_format => {
+            f.update_subtitle_entries(&shifted_timespans)?;
+            f
+        }
+        _ => {
+            if output_file_format != SubtitleFormat::SubStationAlpha {
+                pwarning(format!(
+                    "converting from '{:?}' to '{:?}'; any styling of the incorrect file is dropped, only the plain dialogue text is carried over",
+                    incorrect_file_format,
+                    output_file_format
+                ));
+            }
+            convert_entries_to_format(output_file_format, &shifted_timespans, sub_fps_ref)?
+        }
+    };
     write_data_to_file(output_file_path, correct_file.to_data()?)?;
 
-    Ok(())
+    Ok(any_entries_dropped)
 }
 
 fn main() {
     match run() {
-        Ok(_) => std::process::exit(0),
+        Ok(any_entries_dropped) => {
+            if any_entries_dropped {
+                pwarning("some damaged subtitle entries were skipped because of --skip-errors; the output may be incomplete");
+            }
+            std::process::exit(if any_entries_dropped { 2 } else { 0 });
+        }
         Err(e) => {
             perror(format!("error: {}", e));
 